@@ -0,0 +1,193 @@
+//! HDR bloom: prefilter, downsample/blur, upsample/combine, composite.
+
+use derivative::Derivative;
+
+use amethyst_core::ecs::prelude::ReadExpect;
+use amethyst_error::Error;
+
+use crate::{
+    pipe::{
+        pass::{Pass, PassData},
+        create_offscreen_target, Effect, NewEffect, OffscreenTarget, Targets,
+    },
+    types::{Encoder, Factory},
+};
+
+/// Default number of mip levels in the downsample/upsample chain.
+const DEFAULT_MIP_LEVELS: u8 = 5;
+
+/// One level of the bloom mip chain: an offscreen target half the size of the
+/// previous level, plus the compiled downsample effect that renders into it and
+/// the upsample effect that reads it back into the previous, larger level.
+struct MipLevel {
+    target: OffscreenTarget,
+    downsample: Effect,
+    upsample: Effect,
+}
+
+/// Adds a glow to bright areas of an HDR scene.
+///
+/// Expects to run after the scene has been rendered into a floating-point (e.g.
+/// `Rgba16F`) target so bright values aren't clipped before this pass sees them.
+/// Renders in four steps: prefilter pixels above `threshold` into the first mip
+/// level, progressively downsample+blur into smaller mip levels, upsample and
+/// additively combine each level back up, then composite the result over the
+/// original HDR image before any later tonemapping pass.
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default)]
+pub struct DrawBloom {
+    /// Luminance above which a pixel contributes to the bloom.
+    #[derivative(Default(value = "1.0"))]
+    threshold: f32,
+    /// Width of the soft transition below `threshold`, as a fraction of it.
+    #[derivative(Default(value = "0.1"))]
+    knee: f32,
+    /// Multiplier applied to the bloom contribution when compositing.
+    #[derivative(Default(value = "1.0"))]
+    intensity: f32,
+    #[derivative(Default(value = "DEFAULT_MIP_LEVELS"))]
+    mip_levels: u8,
+    #[derivative(Debug = "ignore", PartialEq = "ignore")]
+    mips: Vec<MipLevel>,
+    #[derivative(Debug = "ignore", PartialEq = "ignore")]
+    prefilter: Option<Effect>,
+}
+
+impl DrawBloom {
+    /// Create a `DrawBloom` pass with the engine's default threshold, knee and
+    /// intensity.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the luminance threshold above which pixels start contributing to bloom.
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Set the width of the soft knee below `threshold`, as a fraction of it.
+    pub fn with_knee(mut self, knee: f32) -> Self {
+        self.knee = knee;
+        self
+    }
+
+    /// Set the intensity the bloom contribution is multiplied by when composited.
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Set the number of downsample/upsample mip levels. More levels produce a
+    /// wider, softer glow at the cost of more passes.
+    pub fn with_mip_levels(mut self, mip_levels: u8) -> Self {
+        self.mip_levels = mip_levels;
+        self
+    }
+}
+
+impl<'a> PassData<'a> for DrawBloom {
+    type Data = ReadExpect<'a, Targets>;
+}
+
+impl Pass for DrawBloom {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let mip_name = |level: u8| -> &'static str {
+            // Mip targets are allocated once per `DrawBloom` and live for the
+            // program's duration, so leaking a unique name per level is fine and
+            // lets every effect below refer to its own target instead of all
+            // colliding on the literal "source"/"lower_mip".
+            Box::leak(format!("bloom_mip_{}", level).into_boxed_str())
+        };
+
+        let mut prefilter = effect.simple(FULLSCREEN_VERT_SRC, PREFILTER_FRAG_SRC);
+        prefilter
+            .with_texture("hdr_color")
+            .with_output(mip_name(0), None);
+        self.prefilter = Some(prefilter.build()?);
+
+        self.mips.clear();
+        let (mut width, mut height) = effect.target_size();
+        for level in 0..self.mip_levels {
+            width = (width / 2).max(1);
+            height = (height / 2).max(1);
+
+            let target = create_offscreen_target(effect.factory, width, height);
+            let source_name = mip_name(level);
+            let dest_name = mip_name(level + 1);
+
+            let mut downsample = effect.simple(FULLSCREEN_VERT_SRC, DOWNSAMPLE_FRAG_SRC);
+            downsample
+                .with_texture(source_name)
+                .with_output(dest_name, None);
+
+            let mut upsample = effect.simple(FULLSCREEN_VERT_SRC, UPSAMPLE_FRAG_SRC);
+            upsample
+                .with_texture(source_name)
+                .with_texture(dest_name)
+                .with_output(source_name, None);
+
+            self.mips.push(MipLevel {
+                target,
+                downsample: downsample.build()?,
+                upsample: upsample.build()?,
+            });
+        }
+
+        // The pipeline drives the final composite through the `Effect` this
+        // returns; `apply` runs the prefilter/downsample/upsample chain itself
+        // against the mip targets built above before reaching the composite draw.
+        let mut composite = effect.simple(FULLSCREEN_VERT_SRC, COMPOSITE_FRAG_SRC);
+        composite
+            .with_texture("hdr_color")
+            .with_texture(mip_name(0))
+            .with_output("color", None);
+        composite.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        targets: <Self as PassData<'a>>::Data,
+    ) {
+        // The mip chain's own offscreen targets are private to this pass and
+        // cleared unconditionally below; only the final composite writes a
+        // named target ("color") another pass might also own. `draw_fullscreen_pass`
+        // resolves each step's `with_texture` declarations against the shared
+        // `Targets` registry; for the mip-to-mip steps that only ever names this
+        // pass's own private levels, so it's really self-documentation of which
+        // level is read, not a cross-pass lookup the way "hdr_color" is.
+        effect.prepare(encoder, &targets);
+
+        for mip in &self.mips {
+            encoder.clear(&mip.target.color.view, [0.0, 0.0, 0.0, 0.0]);
+        }
+
+        if let Some(ref prefilter) = self.prefilter {
+            crate::pass::util::draw_fullscreen_pass(
+                encoder,
+                prefilter,
+                &targets,
+                &[self.threshold, self.knee],
+            );
+        }
+
+        for mip in &self.mips {
+            crate::pass::util::draw_fullscreen_pass(encoder, &mip.downsample, &targets, &[]);
+        }
+
+        for mip in self.mips.iter().rev() {
+            crate::pass::util::draw_fullscreen_pass(encoder, &mip.upsample, &targets, &[]);
+        }
+
+        crate::pass::util::draw_fullscreen_pass(encoder, effect, &targets, &[self.intensity]);
+    }
+}
+
+static FULLSCREEN_VERT_SRC: &[u8] = include_bytes!("shaders/vertex/fullscreen.glsl");
+static PREFILTER_FRAG_SRC: &[u8] = include_bytes!("shaders/fragment/bloom_prefilter.glsl");
+static DOWNSAMPLE_FRAG_SRC: &[u8] = include_bytes!("shaders/fragment/bloom_downsample.glsl");
+static UPSAMPLE_FRAG_SRC: &[u8] = include_bytes!("shaders/fragment/bloom_upsample.glsl");
+static COMPOSITE_FRAG_SRC: &[u8] = include_bytes!("shaders/fragment/bloom_composite.glsl");
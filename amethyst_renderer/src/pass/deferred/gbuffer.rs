@@ -0,0 +1,187 @@
+//! G-buffer pass: writes per-pixel material attributes for deferred lighting.
+
+use std::marker::PhantomData;
+
+use derivative::Derivative;
+use gfx::pso::buffer::ElemStride;
+use glsl_layout::Uniform;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    ecs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    hidden::{Hidden, HiddenPropagate},
+    mesh::{Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults},
+    pass::util::{get_camera, pack_pbr_input, PbrInput, VertexArgs},
+    pipe::{
+        create_offscreen_target,
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect, Targets,
+    },
+    tex::Texture,
+    types::{Encoder, Factory},
+    vertex::{Normal, Position, Query, TexCoord},
+    visibility::Visibility,
+    Rgba,
+};
+
+use super::*;
+
+/// Names of the render targets the G-buffer pass writes, in the order they must be
+/// bound to the fragment shader's multiple outputs.
+pub const GBUFFER_TARGETS: [&str; 3] = ["albedo_emission", "normal", "material"];
+
+/// Writes per-pixel `albedo`/`emission`, world-space `normal` and packed
+/// `metallic`/`roughness` into separate render targets instead of shading
+/// directly, so a later [`DrawDeferredLighting`](super::lighting::DrawDeferredLighting)
+/// pass can light the scene once per screen pixel rather than once per fragment.
+///
+/// Builds the same [`PbrInput`] the forward shader would, then packs it into the
+/// attachment formats instead of shading it immediately.
+///
+/// # Type Parameters
+///
+/// * `V`: `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position, Normal, TexCoord)>, Self: Pass"))]
+pub struct DrawGBuffer<V> {
+    _pd: PhantomData<V>,
+}
+
+impl<V> DrawGBuffer<V>
+where
+    V: Query<(Position, Normal, TexCoord)>,
+    Self: Pass,
+{
+    /// Create an instance of the `DrawGBuffer` pass.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawGBuffer<V>
+where
+    V: Query<(Position, Normal, TexCoord)>,
+{
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, Targets>,
+        Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, Material>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Rgba>,
+    );
+}
+
+impl<V> Pass for DrawGBuffer<V>
+where
+    V: Query<(Position, Normal, TexCoord)>,
+{
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use std::mem;
+
+        // Allocate each G-buffer attachment as a real offscreen target and register
+        // both halves (the view this pass writes, the view `DrawDeferredLighting`
+        // later samples) under the same name, so `with_texture`/`with_output` calls
+        // that name resolve to something instead of declaring an attachment that
+        // never exists.
+        let (width, height) = effect.target_size();
+        for &name in &GBUFFER_TARGETS {
+            let target = create_offscreen_target(effect.factory, width, height);
+            effect.targets.insert_color(name, target.color);
+            effect.targets.insert_view(name, target.view);
+        }
+
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        builder
+            .with_raw_constant_buffer(
+                "VertexArgs",
+                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0)
+            .with_multiple_outputs(GBUFFER_TARGETS.iter().copied(), Some(DepthMode::LessEqualWrite));
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (
+            active,
+            camera,
+            mesh_storage,
+            tex_storage,
+            material_defaults,
+            targets,
+            visibility,
+            hidden,
+            hidden_prop,
+            mesh,
+            material,
+            global,
+            rgba,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        effect.prepare(encoder, &targets);
+        let camera = get_camera(active, &camera, &global);
+
+        match visibility {
+            None => {
+                for (mesh, material, global, rgba, _, _) in (
+                    &mesh,
+                    &material,
+                    &global,
+                    rgba.maybe(),
+                    !&hidden,
+                    !&hidden_prop,
+                )
+                    .join()
+                {
+                    if let Some(mesh) = mesh_storage.get(mesh) {
+                        let input = PbrInput::build(mesh, material, &tex_storage, &material_defaults, rgba);
+                        pack_pbr_input(encoder, effect, camera, global, input);
+                    }
+                }
+            }
+            Some(ref visibility) => {
+                // Only opaque entities belong in the G-buffer: a single-sample
+                // target can't represent blended surfaces, so `visible_ordered`
+                // (transparent) entities are left for the forward transparent
+                // pass to draw instead.
+                for (mesh, material, global, rgba, _) in (
+                    &mesh,
+                    &material,
+                    &global,
+                    rgba.maybe(),
+                    &visibility.visible_unordered,
+                )
+                    .join()
+                {
+                    if let Some(mesh) = mesh_storage.get(mesh) {
+                        let input = PbrInput::build(mesh, material, &tex_storage, &material_defaults, rgba);
+                        pack_pbr_input(encoder, effect, camera, global, input);
+                    }
+                }
+            }
+        }
+    }
+}
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/gbuffer.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/gbuffer.glsl");
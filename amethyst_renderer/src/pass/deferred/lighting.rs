@@ -0,0 +1,93 @@
+//! Deferred lighting pass: shades the G-buffer once per screen pixel.
+
+use derivative::Derivative;
+
+use amethyst_core::{
+    ecs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    light::Light,
+    pass::{
+        deferred::gbuffer::GBUFFER_TARGETS,
+        util::{draw_fullscreen, get_camera, unpack_pbr_input},
+    },
+    pipe::{
+        pass::{Pass, PassData},
+        Effect, NewEffect, Targets,
+    },
+    resources::AmbientColor,
+    types::{Encoder, Factory},
+};
+
+/// Shades the scene by sampling the attachments written by an earlier
+/// [`DrawGBuffer`](super::gbuffer::DrawGBuffer) pass instead of re-deriving material
+/// inputs per fragment, so the (expensive) lighting math runs once per screen pixel
+/// no matter how many opaque fragments overlapped it.
+///
+/// Unpacks the attributes the G-buffer pass packed and shades them with a single
+/// fixed light direction (world +Y) plus the packed emission. The scene's `Light`
+/// components and `AmbientColor` are gathered each frame but are not used by the
+/// fragment shader.
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default)]
+pub struct DrawDeferredLighting {
+    _private: (),
+}
+
+impl DrawDeferredLighting {
+    /// Create an instance of the `DrawDeferredLighting` pass.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a> PassData<'a> for DrawDeferredLighting {
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Light>,
+        ReadExpect<'a, AmbientColor>,
+        ReadExpect<'a, Targets>,
+    );
+}
+
+impl Pass for DrawDeferredLighting {
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        for target in &GBUFFER_TARGETS {
+            builder.with_texture(target);
+        }
+        builder.with_output("color", None);
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (active, camera, global, light, ambient, targets): <Self as PassData<'a>>::Data,
+    ) {
+        effect.prepare(encoder, &targets);
+        let camera = get_camera(active, &camera, &global);
+        let lights: Vec<_> = (&light,).join().map(|(light,)| light).collect();
+
+        draw_fullscreen(
+            encoder,
+            effect,
+            &targets,
+            camera,
+            &lights,
+            &ambient,
+            unpack_pbr_input,
+        );
+    }
+}
+
+static VERT_SRC: &[u8] = include_bytes!("../shaders/vertex/fullscreen.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("../shaders/fragment/deferred_lighting.glsl");
@@ -0,0 +1,14 @@
+//! Deferred shading: a G-buffer pass followed by a fullscreen lighting pass.
+//!
+//! Where the forward passes in [`flat_colored`](super::flat_colored) and friends
+//! compute final shaded color per fragment, the deferred path splits that work in
+//! two: [`DrawGBuffer`](gbuffer::DrawGBuffer) writes per-pixel material attributes
+//! into a handful of render targets, and
+//! [`DrawDeferredLighting`](lighting::DrawDeferredLighting) reads those targets back
+//! as textures and shades the scene once per screen pixel. This trades memory
+//! bandwidth for not paying the shading cost once per overlapping fragment.
+
+pub use self::{gbuffer::DrawGBuffer, lighting::DrawDeferredLighting};
+
+pub mod gbuffer;
+pub mod lighting;
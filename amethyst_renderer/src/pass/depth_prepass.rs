@@ -0,0 +1,165 @@
+//! Depth-only prepass to eliminate overdraw on a following shading pass.
+
+use std::marker::PhantomData;
+
+use derivative::Derivative;
+use gfx::pso::buffer::ElemStride;
+use glsl_layout::Uniform;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    ecs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    transform::GlobalTransform,
+};
+use amethyst_error::Error;
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    hidden::{Hidden, HiddenPropagate},
+    mesh::{Mesh, MeshHandle},
+    mtl::{Material, MaterialDefaults},
+    pass::util::{draw_mesh, get_camera, VertexArgs},
+    pipe::{
+        pass::{Pass, PassData},
+        DepthMode, Effect, NewEffect, Targets,
+    },
+    tex::Texture,
+    types::{Encoder, Factory},
+    vertex::{Position, Query},
+    visibility::Visibility,
+};
+
+/// Renders only the depth of opaque geometry, with a trivial fragment shader that
+/// writes no color.
+///
+/// Run this before a shading pass configured with
+/// [`with_depth_test_only`](crate::pass::DrawFlatColored::with_depth_test_only) (set
+/// to `DepthMode::Equal` and loading this pass's depth attachment instead of
+/// clearing it) so the expensive fragment shader in that later pass only runs once
+/// per pixel rather than once per overlapping fragment — a large win for scenes with
+/// heavy overdraw.
+///
+/// # Type Parameters
+///
+/// * `V`: `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position,)>, Self: Pass"))]
+pub struct DrawDepthPrepass<V> {
+    _pd: PhantomData<V>,
+}
+
+impl<V> DrawDepthPrepass<V>
+where
+    V: Query<(Position,)>,
+    Self: Pass,
+{
+    /// Create an instance of the `DrawDepthPrepass` pass.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawDepthPrepass<V>
+where
+    V: Query<(Position,)>,
+{
+    type Data = (
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, Targets>,
+        Option<Read<'a, Visibility>>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, Material>,
+        ReadStorage<'a, GlobalTransform>,
+    );
+}
+
+impl<V> Pass for DrawDepthPrepass<V>
+where
+    V: Query<(Position,)>,
+{
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error> {
+        use std::mem;
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        builder
+            .with_raw_constant_buffer(
+                "VertexArgs",
+                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0)
+            .with_depth_buffer(DepthMode::LessEqualWrite);
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (
+            active,
+            camera,
+            mesh_storage,
+            tex_storage,
+            material_defaults,
+            targets,
+            visibility,
+            hidden,
+            hidden_prop,
+            mesh,
+            material,
+            global,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        effect.prepare(encoder, &targets);
+        let camera = get_camera(active, &camera, &global);
+
+        // Must match whatever `DrawFlatColored::with_depth_test_only` draws, or
+        // this pass will write depth for entities the color pass culls and the
+        // later `DepthMode::Equal` test will occlude real visible geometry behind
+        // them.
+        let mut draw = |mesh, global| {
+            draw_mesh(
+                encoder,
+                effect,
+                false,
+                mesh_storage.get(mesh),
+                None,
+                &tex_storage,
+                None,
+                &material_defaults,
+                None,
+                camera,
+                Some(global),
+                &[V::QUERIED_ATTRIBUTES],
+                &[],
+            );
+        };
+
+        match visibility {
+            None => {
+                for (mesh, _, global, _, _) in
+                    (&mesh, &material, &global, !&hidden, !&hidden_prop).join()
+                {
+                    draw(mesh, global);
+                }
+            }
+            Some(ref visibility) => {
+                for (mesh, _, global, _) in
+                    (&mesh, &material, &global, &visibility.visible_unordered).join()
+                {
+                    draw(mesh, global);
+                }
+            }
+        }
+    }
+}
+
+static VERT_SRC: &[u8] = include_bytes!("shaders/vertex/basic.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("shaders/fragment/depth_only.glsl");
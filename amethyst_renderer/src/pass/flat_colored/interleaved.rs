@@ -1,15 +1,18 @@
 //! Simple flat forward drawing pass.
 
-use std::marker::PhantomData;
+use std::{cmp::Reverse, collections::HashMap, marker::PhantomData};
 
 use derivative::Derivative;
-use gfx::pso::buffer::ElemStride;
+use gfx::{
+    format::{ChannelType, Format, SurfaceType},
+    pso::buffer::ElemStride,
+};
 use gfx_core::state::{Blend, ColorMask};
 use glsl_layout::Uniform;
 
 use amethyst_assets::AssetStorage;
 use amethyst_core::{
-    ecs::prelude::{Join, Read, ReadExpect, ReadStorage},
+    ecs::prelude::{Entities, Entity, Join, Read, ReadExpect, ReadStorage},
     transform::GlobalTransform,
 };
 use amethyst_error::Error;
@@ -19,10 +22,13 @@ use crate::{
     hidden::{Hidden, HiddenPropagate},
     mesh::{Mesh, MeshHandle},
     mtl::{Material, MaterialDefaults},
-    pass::util::{draw_mesh, get_camera, VertexArgs},
+    pass::{
+        phase::{draw_phase, sort_phase, PhaseItem, RenderCommand},
+        util::{bind_material, draw_bound_mesh, draw_mesh_instanced, get_camera, VertexArgs},
+    },
     pipe::{
         pass::{Pass, PassData},
-        DepthMode, Effect, NewEffect,
+        DepthMode, Effect, NewEffect, Targets,
     },
     tex::Texture,
     types::{Encoder, Factory},
@@ -33,6 +39,183 @@ use crate::{
 
 use super::*;
 
+/// Per-instance data for a single `DrawFlatColored` draw: the entity's model matrix
+/// and tint, uploaded once per unique mesh rather than once per entity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+struct InstanceArgs {
+    model: [[f32; 4]; 4],
+    rgba: [f32; 4],
+}
+
+impl InstanceArgs {
+    // A GPU vertex attribute slot holds at most 4 floats (16 bytes), so the 4x4
+    // `model` matrix (64 bytes) needs one attribute per column rather than a
+    // single oversized one; `rgba` fits in the slot right after.
+    const ATTRIBUTES: &'static [(&'static str, Format)] = &[
+        ("model_0", Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float)),
+        ("model_1", Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float)),
+        ("model_2", Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float)),
+        ("model_3", Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float)),
+        ("rgba", Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float)),
+    ];
+
+    fn new(global: &GlobalTransform, rgba: Option<&Rgba>) -> Self {
+        InstanceArgs {
+            model: global.0.into(),
+            rgba: rgba.cloned().unwrap_or(Rgba::WHITE).into(),
+        }
+    }
+}
+
+/// A group of entities sharing a `MeshHandle`, drawn with a single instanced call.
+///
+/// `material` is taken from the first entity joined into the group; entities that
+/// share a mesh are expected to share a material too (e.g. instances of the same
+/// decoration), since a single draw call can only bind one set of textures.
+#[derive(Clone, Debug, Default)]
+struct Batch {
+    material: Option<Material>,
+    instances: Vec<InstanceArgs>,
+}
+
+/// World-space translation of a `GlobalTransform`'s underlying matrix.
+fn translation(global: &GlobalTransform) -> [f32; 3] {
+    [global.0[(0, 3)], global.0[(1, 3)], global.0[(2, 3)]]
+}
+
+/// A squared camera-space distance, orderable despite `f32` not being `Ord`.
+///
+/// Assumes distances are never `NaN`, which holds as long as they come from a
+/// well-formed `GlobalTransform`.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+struct Distance(f32);
+
+impl Eq for Distance {}
+
+impl Ord for Distance {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.partial_cmp(other).unwrap_or(std::cmp::Ordering::Equal)
+    }
+}
+
+/// One opaque entity's contribution to the instanced flat-color phase.
+///
+/// Unlike `TransparentItem`, draw order doesn't affect correctness here (the
+/// entities below are batched by mesh, not drawn individually), so the sort key
+/// is trivial. The point of routing the no-visibility and `visible_unordered`
+/// cases through a `PhaseItem` at all is to extract both through the same
+/// machinery as the transparent phase instead of two near-identical hand-joined
+/// loops.
+struct OpaqueItem {
+    entity: Entity,
+}
+
+impl PhaseItem for OpaqueItem {
+    type SortKey = ();
+
+    fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    fn sort_key(&self) -> Self::SortKey {}
+}
+
+/// One transparent entity's contribution to the back-to-front draw phase.
+///
+/// Sorted by `Reverse(distance)` so the farthest entities (drawn first) sort
+/// before nearer ones, giving correct over-blending regardless of the order
+/// entities were joined from the ECS in.
+struct TransparentItem {
+    entity: Entity,
+    distance: Distance,
+}
+
+impl PhaseItem for TransparentItem {
+    type SortKey = Reverse<Distance>;
+
+    fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    fn sort_key(&self) -> Self::SortKey {
+        Reverse(self.distance)
+    }
+}
+
+/// Binds an entity's material so the following `DrawMeshCommand` draws with it.
+struct BindMaterialCommand<V>(PhantomData<V>);
+
+impl<'a, V> RenderCommand<'a, TransparentItem, Option<Camera>> for BindMaterialCommand<V>
+where
+    V: Query<(Position, Color)>,
+{
+    type Param = (
+        Read<'a, AssetStorage<Texture>>,
+        ReadExpect<'a, MaterialDefaults>,
+        ReadStorage<'a, Material>,
+        ReadStorage<'a, Rgba>,
+    );
+
+    fn render(
+        _camera: &Option<Camera>,
+        (tex_storage, material_defaults, material, rgba): &Self::Param,
+        item: &TransparentItem,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+    ) {
+        bind_material(
+            encoder,
+            effect,
+            tex_storage,
+            material.get(item.entity),
+            material_defaults,
+            rgba.get(item.entity),
+        );
+    }
+}
+
+/// Binds an entity's mesh and records its draw call, using whatever material the
+/// preceding `BindMaterialCommand` bound.
+struct DrawMeshCommand<V>(PhantomData<V>);
+
+impl<'a, V> RenderCommand<'a, TransparentItem, Option<Camera>> for DrawMeshCommand<V>
+where
+    V: Query<(Position, Color)>,
+{
+    type Param = (
+        Read<'a, AssetStorage<Mesh>>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, GlobalTransform>,
+    );
+
+    fn render(
+        camera: &Option<Camera>,
+        (mesh_storage, mesh, global): &Self::Param,
+        item: &TransparentItem,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+    ) {
+        if let Some(mesh) = mesh.get(item.entity) {
+            draw_bound_mesh(
+                encoder,
+                effect,
+                mesh_storage.get(mesh),
+                *camera,
+                global.get(item.entity),
+                &[V::QUERIED_ATTRIBUTES],
+            );
+        }
+    }
+}
+
+/// The two steps `DrawFlatColored`'s transparent phase composes per item: bind
+/// material, then bind mesh and draw. Composed as a tuple through
+/// `pass::phase`'s generic `RenderCommand` impl instead of inlining both steps
+/// into one monolithic command, so each step only fetches the `SystemData` it
+/// actually needs.
+type DrawFlatColoredCommand<V> = (BindMaterialCommand<V>, DrawMeshCommand<V>);
+
 /// Draw mesh without lighting
 ///
 /// See the [crate level documentation](index.html) for information about interleaved and separate
@@ -46,6 +229,10 @@ use super::*;
 pub struct DrawFlatColored<V> {
     _pd: PhantomData<V>,
     transparency: Option<(ColorMask, Blend, Option<DepthMode>)>,
+    depth_test_only: bool,
+    /// Per-mesh instance batches, reused every frame to avoid reallocating.
+    #[derivative(Debug = "ignore", PartialEq = "ignore")]
+    batches: HashMap<MeshHandle, Batch>,
 }
 
 impl<V> DrawFlatColored<V>
@@ -68,6 +255,20 @@ where
         self.transparency = Some((mask, blend, depth));
         self
     }
+
+    /// Skip clearing and writing the depth buffer; only test against depth already
+    /// populated by an earlier pass (e.g. `DrawDepthPrepass`). Has no effect when
+    /// `with_transparency` is also set; the transparency settings' depth mode is
+    /// used instead.
+    ///
+    /// Pair this with a prepass that wrote depth for the same opaque geometry: since
+    /// the nearest surface per pixel is already resolved, this pass's (comparatively
+    /// expensive) fragment shader only ever runs once per pixel instead of once per
+    /// overlapping fragment.
+    pub fn with_depth_test_only(mut self) -> Self {
+        self.depth_test_only = true;
+        self
+    }
 }
 
 impl<'a, V> PassData<'a> for DrawFlatColored<V>
@@ -75,11 +276,13 @@ where
     V: Query<(Position, Color)>,
 {
     type Data = (
+        Entities<'a>,
         Read<'a, ActiveCamera>,
         ReadStorage<'a, Camera>,
         Read<'a, AssetStorage<Mesh>>,
         Read<'a, AssetStorage<Texture>>,
         ReadExpect<'a, MaterialDefaults>,
+        ReadExpect<'a, Targets>,
         Option<Read<'a, Visibility>>,
         ReadStorage<'a, Hidden>,
         ReadStorage<'a, HiddenPropagate>,
@@ -103,10 +306,23 @@ where
                 mem::size_of::<<VertexArgs as Uniform>::Std140>(),
                 1,
             )
-            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0);
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as ElemStride, 0)
+            .with_raw_vertex_buffer_instanced(
+                InstanceArgs::ATTRIBUTES,
+                mem::size_of::<InstanceArgs>() as ElemStride,
+                1,
+            );
         match self.transparency {
-            Some((mask, blend, depth)) => builder.with_blended_output("color", mask, blend, depth),
-            None => builder.with_output("color", Some(DepthMode::LessEqualWrite)),
+            Some((mask, blend, depth)) => {
+                builder.with_blended_output("color", mask, blend, depth);
+            }
+            None if self.depth_test_only => {
+                builder.with_output("color", None);
+                builder.with_depth_buffer_load(DepthMode::Equal);
+            }
+            None => {
+                builder.with_output("color", Some(DepthMode::LessEqualWrite));
+            }
         };
         builder.build()
     }
@@ -117,11 +333,13 @@ where
         effect: &mut Effect,
         _factory: Factory,
         (
+            entities,
             active,
             camera,
             mesh_storage,
             tex_storage,
             material_defaults,
+            targets,
             visibility,
             hidden,
             hidden_prop,
@@ -131,84 +349,104 @@ where
             rgba,
         ): <Self as PassData<'a>>::Data,
     ) {
+        effect.prepare(encoder, &targets);
+        let active_camera_entity = active.entity;
         let camera = get_camera(active, &camera, &global);
 
-        match visibility {
-            None => {
-                for (mesh, material, global, rgba, _, _) in (
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    !&hidden,
-                    !&hidden_prop,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        false,
-                        mesh_storage.get(mesh),
-                        None,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &[V::QUERIED_ATTRIBUTES],
-                        &[],
-                    );
-                }
+        for batch in self.batches.values_mut() {
+            batch.instances.clear();
+        }
+
+        let mut group = |mesh_handle: &MeshHandle, mat: &Material, global, rgba| {
+            let batch = self.batches.entry(mesh_handle.clone()).or_default();
+            // Refreshed from whichever entity is joined first each frame, rather
+            // than only on the batch's first frame, so a material change (or the
+            // original entity despawning while others share its mesh) is picked
+            // up instead of drawing with a stale material forever.
+            if batch.instances.is_empty() {
+                batch.material = Some(mat.clone());
             }
-            Some(ref visibility) => {
-                for (mesh, material, global, rgba, _) in (
-                    &mesh,
-                    &material,
-                    &global,
-                    rgba.maybe(),
-                    &visibility.visible_unordered,
-                )
-                    .join()
-                {
-                    draw_mesh(
-                        encoder,
-                        effect,
-                        false,
-                        mesh_storage.get(mesh),
-                        None,
-                        &tex_storage,
-                        Some(material),
-                        &material_defaults,
-                        rgba,
-                        camera,
-                        Some(global),
-                        &[V::QUERIED_ATTRIBUTES],
-                        &[],
-                    );
-                }
-
-                for entity in &visibility.visible_ordered {
-                    if let Some(mesh) = mesh.get(*entity) {
-                        draw_mesh(
-                            encoder,
-                            effect,
-                            false,
-                            mesh_storage.get(mesh),
-                            None,
-                            &tex_storage,
-                            material.get(*entity),
-                            &material_defaults,
-                            rgba.get(*entity),
-                            camera,
-                            global.get(*entity),
-                            &[V::QUERIED_ATTRIBUTES],
-                            &[],
-                        );
-                    }
-                }
+            batch.instances.push(InstanceArgs::new(global, rgba));
+        };
+
+        // Extract the opaque entities through the same `PhaseItem` machinery as
+        // the transparent phase below, rather than two near-identical hardcoded
+        // joins for the no-visibility and `visible_unordered` cases; only the
+        // join used to build the item list differs between them.
+        let mut opaque_items: Vec<OpaqueItem> = match visibility {
+            None => (&entities, !&hidden, !&hidden_prop)
+                .join()
+                .map(|(entity, _, _)| OpaqueItem { entity })
+                .collect(),
+            Some(ref visibility) => visibility
+                .visible_unordered
+                .iter()
+                .map(|&entity| OpaqueItem { entity })
+                .collect(),
+        };
+        sort_phase(&mut opaque_items);
+        for item in &opaque_items {
+            if let (Some(mesh), Some(mat), Some(global)) = (
+                mesh.get(item.entity),
+                material.get(item.entity),
+                global.get(item.entity),
+            ) {
+                group(mesh, mat, global, rgba.get(item.entity));
             }
         }
+
+        for (mesh_handle, batch) in self.batches.iter().filter(|(_, b)| !b.instances.is_empty()) {
+            draw_mesh_instanced(
+                encoder,
+                effect,
+                true,
+                mesh_storage.get(mesh_handle),
+                Some(batch.instances.len() as u32),
+                &tex_storage,
+                batch.material.as_ref(),
+                &material_defaults,
+                camera,
+                &[V::QUERIED_ATTRIBUTES],
+                &[InstanceArgs::ATTRIBUTES],
+                &batch.instances,
+            );
+        }
+
+        // Drop batches for meshes that weren't present this frame instead of
+        // letting `self.batches` grow forever in a scene with churning MeshHandles.
+        self.batches.retain(|_, batch| !batch.instances.is_empty());
+
+        // Transparent geometry isn't instanced: batching would reorder overlapping
+        // entities and break blending. Instead of trusting `visible_ordered`'s
+        // pre-baked order, extract a `TransparentItem` per entity and sort it
+        // ourselves, so the draw order always matches the current camera position.
+        if let Some(ref visibility) = visibility {
+            let camera_position = active_camera_entity
+                .and_then(|entity| global.get(entity))
+                .map(translation)
+                .unwrap_or([0.0, 0.0, 0.0]);
+
+            let mut items: Vec<TransparentItem> = visibility
+                .visible_ordered
+                .iter()
+                .filter_map(|&entity| {
+                    let [x, y, z] = translation(global.get(entity)?);
+                    let [cx, cy, cz] = camera_position;
+                    let distance_squared =
+                        (x - cx).powi(2) + (y - cy).powi(2) + (z - cz).powi(2);
+                    Some(TransparentItem {
+                        entity,
+                        distance: Distance(distance_squared),
+                    })
+                })
+                .collect();
+            sort_phase(&mut items);
+
+            let param = (
+                (tex_storage, material_defaults, material, rgba),
+                (mesh_storage, mesh, global),
+            );
+            draw_phase::<_, DrawFlatColoredCommand<V>, _>(&camera, &param, &items, encoder, effect);
+        }
     }
 }
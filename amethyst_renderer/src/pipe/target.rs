@@ -0,0 +1,178 @@
+//! Render target attachments that track whether they've been cleared this frame.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use gfx::{
+    handle::{DepthStencilView, RenderTargetView, ShaderResourceView},
+    traits::FactoryExt,
+};
+
+use crate::types::{DepthFormat, Factory, Resources};
+
+/// A color attachment shared across passes within a frame.
+///
+/// The first pass to bind it clears it; every later pass in the same frame that
+/// binds it instead loads the contents the first pass left behind. This lets
+/// several passes (an opaque forward pass, a transparency pass, post-process passes)
+/// render onto the same target back to back without double-clearing it or the
+/// pipeline having to hardcode clear ordering per pass.
+pub struct ColorBuffer {
+    pub(crate) view: RenderTargetView<Resources, [f32; 4]>,
+    cleared_this_frame: AtomicBool,
+}
+
+impl ColorBuffer {
+    /// Wrap a render target view with clear-tracking.
+    pub fn new(view: RenderTargetView<Resources, [f32; 4]>) -> Self {
+        ColorBuffer {
+            view,
+            cleared_this_frame: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns `true` if this is the first bind of the target this frame, and marks
+    /// it as cleared either way. Passes should clear when this returns `true` and
+    /// load otherwise.
+    pub fn should_clear(&self) -> bool {
+        !self.cleared_this_frame.swap(true, Ordering::AcqRel)
+    }
+
+    /// Reset clear-tracking; call once at the start of every frame.
+    pub fn begin_frame(&self) {
+        self.cleared_this_frame.store(false, Ordering::Release);
+    }
+}
+
+/// A render target allocated for intermediate use within a single pipeline, such
+/// as one level of a post-process mip chain, with both the view it's rendered into
+/// and the view later passes sample it through.
+pub struct OffscreenTarget {
+    /// Bound as a color output by the pass that renders into this level.
+    pub color: ColorBuffer,
+    /// Bound as a texture input by passes that sample this level.
+    pub view: ShaderResourceView<Resources, [f32; 4]>,
+}
+
+/// Allocate an `Rgba32F` (`[f32; 4]`) offscreen target of `width`x`height`, e.g. one level of a
+/// bloom downsample/upsample mip chain.
+pub fn create_offscreen_target(factory: &mut Factory, width: u32, height: u32) -> OffscreenTarget {
+    let (_, view, rtv) = factory
+        .create_render_target(width as u16, height as u16)
+        .expect("failed to allocate offscreen render target");
+    OffscreenTarget {
+        color: ColorBuffer::new(rtv),
+        view,
+    }
+}
+
+/// A depth/stencil attachment shared across passes within a frame.
+///
+/// Tracks first-bind-clears-this-frame the same way [`ColorBuffer`] does, so a
+/// depth prepass can own and clear the depth buffer while a later pass configured
+/// to test (but not write) depth automatically loads it instead.
+pub struct DepthBuffer {
+    pub(crate) view: DepthStencilView<Resources, DepthFormat>,
+    cleared_this_frame: AtomicBool,
+}
+
+impl DepthBuffer {
+    /// Wrap a depth/stencil view with clear-tracking.
+    pub fn new(view: DepthStencilView<Resources, DepthFormat>) -> Self {
+        DepthBuffer {
+            view,
+            cleared_this_frame: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns `true` if this is the first bind of the target this frame, and marks
+    /// it as cleared either way.
+    pub fn should_clear(&self) -> bool {
+        !self.cleared_this_frame.swap(true, Ordering::AcqRel)
+    }
+
+    /// Reset clear-tracking; call once at the start of every frame.
+    pub fn begin_frame(&self) {
+        self.cleared_this_frame.store(false, Ordering::Release);
+    }
+}
+
+/// The named color and depth attachments shared across every pass in a pipeline.
+///
+/// Fetched as a resource so a pass's `apply` can look up the buffer behind the
+/// names it declared to `EffectBuilder`, then ask its compiled `Effect` whether to
+/// clear or load it via [`Effect::prepare`](crate::pipe::Effect::prepare).
+#[derive(Default)]
+pub struct Targets {
+    colors: HashMap<&'static str, ColorBuffer>,
+    depth: Option<DepthBuffer>,
+    views: HashMap<&'static str, ShaderResourceView<Resources, [f32; 4]>>,
+}
+
+impl Targets {
+    /// An empty target registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a named color attachment, e.g. `"color"` or one of `GBUFFER_TARGETS`.
+    pub fn insert_color(&mut self, name: &'static str, buffer: ColorBuffer) {
+        self.colors.insert(name, buffer);
+    }
+
+    /// Register the pipeline's single shared depth/stencil attachment.
+    pub fn set_depth(&mut self, buffer: DepthBuffer) {
+        self.depth = Some(buffer);
+    }
+
+    /// Register the view a later pass samples a named color attachment through,
+    /// e.g. the `ShaderResourceView` half of an [`OffscreenTarget`] allocated for
+    /// one of `GBUFFER_TARGETS`.
+    pub fn insert_view(&mut self, name: &'static str, view: ShaderResourceView<Resources, [f32; 4]>) {
+        self.views.insert(name, view);
+    }
+
+    /// Look up a color attachment by the name a pass declared via `with_output`.
+    pub fn color(&self, name: &str) -> Option<&ColorBuffer> {
+        self.colors.get(name)
+    }
+
+    /// The pipeline's shared depth/stencil attachment, if one has been registered.
+    pub fn depth(&self) -> Option<&DepthBuffer> {
+        self.depth.as_ref()
+    }
+
+    /// Look up the view a pass declared via `with_texture` should sample, i.e. the
+    /// render target an earlier pass registered under the same name.
+    pub fn view(&self, name: &str) -> Option<&ShaderResourceView<Resources, [f32; 4]>> {
+        self.views.get(name)
+    }
+
+    /// Reset clear-tracking on every contained buffer; call once at the start of
+    /// every frame before the first pass runs.
+    pub fn begin_frame(&self) {
+        for buffer in self.colors.values() {
+            buffer.begin_frame();
+        }
+        if let Some(ref depth) = self.depth {
+            depth.begin_frame();
+        }
+    }
+}
+
+/// Resets every registered target's clear-tracking at the start of each frame, so
+/// the first pass to bind a given name clears it and every later pass sharing that
+/// name this frame loads it instead, per [`Effect::prepare`](crate::pipe::Effect::prepare).
+///
+/// The render bundle must schedule this system before any `Pass::apply` runs.
+pub struct BeginFrameSystem;
+
+impl<'a> amethyst_core::ecs::prelude::System<'a> for BeginFrameSystem {
+    type SystemData = amethyst_core::ecs::prelude::ReadExpect<'a, Targets>;
+
+    fn run(&mut self, targets: Self::SystemData) {
+        targets.begin_frame();
+    }
+}